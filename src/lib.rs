@@ -13,5 +13,10 @@ extern crate alloc;
 pub mod raw {
     pub mod ringbuf;
     pub mod buffer;
+    pub mod alloc;
+    pub mod bump;
 }
 
+/// Safe building blocks layered over the unsafe `raw` primitives.
+pub mod util;
+
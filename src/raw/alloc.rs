@@ -1,12 +1,260 @@
-use alloc::heap;
 use std::mem;
 use std::num::Int;
 
+/// The layout of a block of memory: its size in bytes and its alignment.
+///
+/// Bundling the two together lets callers compute `size_of`/`align_of` once
+/// and thread the result through `alloc`/`realloc`/`dealloc`, rather than
+/// recomputing it on every call.
+pub struct Layout {
+    size: uint,
+    align: uint,
+}
+
+impl Copy for Layout {}
+
+impl Layout {
+    /// Construct a `Layout` from a raw size and alignment.
+    #[inline]
+    pub fn from_size_align(size: uint, align: uint) -> Layout {
+        Layout { size: size, align: align }
+    }
+
+    /// The layout of an array of `capacity` `T`s, checking for overflow.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `capacity * size_of::<T>()` overflows `uint`.
+    ///
+    /// ## Invariants
+    ///   - `capacity` is non-zero.
+    ///   - `T` is not a zero-sized-type.
+    #[inline]
+    pub fn array<T>(capacity: uint) -> Layout {
+        Layout {
+            size: allocation_size::<T>(capacity),
+            align: mem::align_of::<T>(),
+        }
+    }
+
+    /// The layout of an array of `capacity` `T`s, reporting overflow as an
+    /// error rather than panicking.
+    ///
+    /// ## Invariants
+    ///   - `capacity` is non-zero.
+    ///   - `T` is not a zero-sized-type.
+    #[inline]
+    pub fn try_array<T>(capacity: uint) -> Result<Layout, CollectionAllocErr> {
+        debug_assert!(capacity != 0);
+        debug_assert!(mem::size_of::<T>() != 0);
+
+        match capacity.checked_mul(mem::size_of::<T>()) {
+            Some(size) => Ok(Layout::from_size_align(size, mem::align_of::<T>())),
+            None => Err(CollectionAllocErr::CapacityOverflow),
+        }
+    }
+
+    /// The size of this layout, in bytes.
+    #[inline]
+    pub fn size(&self) -> uint { self.size }
+
+    /// The alignment of this layout, in bytes.
+    #[inline]
+    pub fn align(&self) -> uint { self.align }
+}
+
+/// The error returned when an allocator cannot satisfy a request.
+pub struct AllocErr;
+
+impl Copy for AllocErr {}
+
+/// A pluggable memory allocator.
+///
+/// Modeled on the allocator-API `Alloc`/`AllocRef` design: every operation
+/// takes a pre-computed `Layout` and reports failure through `AllocErr`
+/// instead of aborting, so lock-free structures can be built on arenas,
+/// pools, or instrumented allocators as well as the system heap.
+///
+/// The pointer returned by `alloc`/`realloc` is non-null on `Ok`.
+pub trait Alloc {
+    /// Allocate a block of memory fitting `layout`.
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr>;
+
+    /// Resize the block at `ptr` from `old` to `new`.
+    unsafe fn realloc(&mut self, ptr: *mut u8, old: Layout, new: Layout)
+        -> Result<*mut u8, AllocErr>;
+
+    /// Deallocate the block at `ptr`, which must fit `layout`.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+}
+
+/// The default allocator, wrapping the system heap (`alloc::heap`).
+pub struct Global;
+
+impl Copy for Global {}
+
+impl Alloc for Global {
+    #[inline]
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let ptr = sys::allocate(layout.size, layout.align);
+        if ptr.is_null() { Err(AllocErr) } else { Ok(ptr) }
+    }
+
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: *mut u8, old: Layout, new: Layout)
+            -> Result<*mut u8, AllocErr> {
+        let ptr = sys::reallocate(ptr, old.size, new.size, new.align);
+        if ptr.is_null() { Err(AllocErr) } else { Ok(ptr) }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        sys::deallocate(ptr, layout.size, layout.align)
+    }
+}
+
+/// The default backend: the unstable `alloc::heap` API, which honors the
+/// requested alignment.
+#[cfg(not(feature = "stable"))]
+mod sys {
+    use alloc::heap;
+
+    #[inline]
+    pub unsafe fn allocate(size: uint, align: uint) -> *mut u8 {
+        heap::allocate(size, align)
+    }
+
+    #[inline]
+    pub unsafe fn reallocate(ptr: *mut u8, old_size: uint, new_size: uint, align: uint) -> *mut u8 {
+        heap::reallocate(ptr, old_size, new_size, align)
+    }
+
+    #[inline]
+    pub unsafe fn deallocate(ptr: *mut u8, size: uint, align: uint) {
+        heap::deallocate(ptr, size, align)
+    }
+}
+
+/// A stable-toolchain backend built entirely on `Vec`, selected with the
+/// `stable` feature.
+///
+/// The pointer is extracted from a `Vec<u8>` and the allocation's bookkeeping
+/// is recovered by reconstructing that `Vec` with `from_raw_parts`. The
+/// tradeoffs are that everything is aligned only to `u8` — the requested
+/// alignment must be `1` — and that out-of-memory aborts inside `Vec` rather
+/// than surfacing as an error. `Buffer` therefore stays alignment-correct only
+/// on the default heap backend.
+///
+/// ## Soundness
+///
+/// Because this backend cannot honor an alignment greater than `1`, every
+/// operation asserts `align == 1`: a `stable` build that tries to back a
+/// `Buffer<T>` with `align_of::<T>() > 1` panics loudly instead of silently
+/// handing out misaligned memory.
+///
+/// Every block's capacity is exactly the `size` it was allocated with, because
+/// `Vec::with_capacity(size)` records exactly `size` (any allocator rounding is
+/// invisible to the `Vec`). Reallocation never reconstructs a `Vec` with a
+/// guessed capacity: it allocates a fresh exact-capacity block, copies the
+/// overlapping prefix across, and frees the old one, so the `at least` contract
+/// of `reserve_exact` can never leave the bookkeeping out of step.
+#[cfg(feature = "stable")]
+mod sys {
+    use std::cmp;
+    use std::mem;
+    use std::ptr;
+    use std::vec::Vec;
+
+    #[inline]
+    pub unsafe fn allocate(size: uint, align: uint) -> *mut u8 {
+        assert!(align == 1, "the stable allocation backend only supports u8 alignment");
+
+        let mut vec: Vec<u8> = Vec::with_capacity(size);
+        let ptr = vec.as_mut_ptr();
+        mem::forget(vec);
+        ptr
+    }
+
+    #[inline]
+    pub unsafe fn reallocate(ptr: *mut u8, old_size: uint, new_size: uint, align: uint) -> *mut u8 {
+        // Allocate an exact-capacity block and move the data, rather than
+        // reconstructing a `Vec` whose real capacity may exceed `new_size`.
+        let new_ptr = allocate(new_size, align);
+        ptr::copy_memory(new_ptr, ptr as *const u8, cmp::min(old_size, new_size));
+        deallocate(ptr, old_size, align);
+        new_ptr
+    }
+
+    #[inline]
+    pub unsafe fn deallocate(ptr: *mut u8, size: uint, align: uint) {
+        assert!(align == 1, "the stable allocation backend only supports u8 alignment");
+
+        // Reconstruct the `Vec` with its exact capacity so the destructor frees it.
+        let _: Vec<u8> = Vec::from_raw_parts(ptr, 0, size);
+    }
+}
+
+/// The error returned by the fallible allocation surface.
+///
+/// Distinguishes an arithmetic overflow while computing the allocation size
+/// from the allocator itself running out of memory, so callers can decide
+/// whether to shrink and retry or give up.
+pub enum CollectionAllocErr {
+    /// `capacity * size_of::<T>()` overflowed `uint`.
+    CapacityOverflow,
+
+    /// The underlying allocator could not satisfy the request.
+    AllocErr,
+}
+
+impl Copy for CollectionAllocErr {}
+
 /// "Allocate" a special allocation of zero size.
 #[inline]
 pub unsafe fn empty<T>() -> *mut T { 1u as *mut T }
 
-/// Allocate space for `capacity` `T`s
+/// Allocate space for `capacity` `T`s, returning an error rather than aborting.
+///
+/// ## Invariants
+///   - `capacity` is non-zero.
+///   - `T` is not a zero-sized-type.
+#[inline]
+pub unsafe fn try_allocate<T>(capacity: uint) -> Result<*mut T, CollectionAllocErr> {
+    debug_assert!(mem::size_of::<T>() != 0);
+    debug_assert!(capacity != 0);
+
+    let layout = try!(Layout::try_array::<T>(capacity));
+    match Global.alloc(layout) {
+        Ok(ptr) => Ok(ptr as *mut T),
+        Err(..) => Err(CollectionAllocErr::AllocErr),
+    }
+}
+
+/// Reallocate `old` so it can hold `new_capacity` `T`s, returning an error
+/// rather than aborting.
+///
+/// ## Invariants
+///   - `old_capacity` is non-zero.
+///   - `new_capacity` is non-zero.
+///   - `T` is not a zero-sized-type.
+///   - `old` is the appropriate size for `old_capacity` `T`s size and was allocated by
+///     the `Global` allocator.
+#[inline]
+pub unsafe fn try_reallocate<T>(old: *mut T, old_capacity: uint, new_capacity: uint)
+        -> Result<*mut T, CollectionAllocErr> {
+    debug_assert!(mem::size_of::<T>() != 0);
+    debug_assert!(old_capacity != 0);
+    debug_assert!(new_capacity != 0);
+
+    let old_layout = try!(Layout::try_array::<T>(old_capacity));
+    let new_layout = try!(Layout::try_array::<T>(new_capacity));
+    match Global.realloc(old as *mut u8, old_layout, new_layout) {
+        Ok(ptr) => Ok(ptr as *mut T),
+        Err(..) => Err(CollectionAllocErr::AllocErr),
+    }
+}
+
+/// Allocate space for `capacity` `T`s through the `Global` allocator.
 ///
 /// ## Panics
 ///
@@ -17,21 +265,13 @@ pub unsafe fn empty<T>() -> *mut T { 1u as *mut T }
 ///   - `T` is not a zero-sized-type.
 #[inline]
 pub unsafe fn allocate<T>(capacity: uint) -> *mut T {
-    let size_of = mem::size_of::<T>();
-    let alignment = mem::align_of::<T>();
-
-    debug_assert!(size_of != 0);
-    debug_assert!(capacity != 0);
-
-    let size = allocation_size::<T>(size_of);
-    let ptr = heap::allocate(size, alignment);
-    if ptr.is_null() { ::alloc::oom() }
-
-    ptr as *mut T
+    match try_allocate::<T>(capacity) {
+        Ok(ptr) => ptr,
+        Err(..) => ::alloc::oom(),
+    }
 }
 
-
-/// Reallocate `old` to a new size, so it can hold `new_capacity` `T`s
+/// Reallocate `old` to a new size, so it can hold `new_capacity` `T`s.
 ///
 /// ## Panics
 ///
@@ -42,40 +282,28 @@ pub unsafe fn allocate<T>(capacity: uint) -> *mut T {
 ///   - `new_capacity` is non-zero.
 ///   - `T` is not a zero-sized-type.
 ///   - `old` is the appropriate size for `old_capacity` `T`s size and was allocated by
-///     the heap allocator.
+///     the `Global` allocator.
 #[inline]
 pub unsafe fn reallocate<T>(old: *mut T, old_capacity: uint, new_capacity: uint) -> *mut T {
-    let size_of = mem::size_of::<T>();
-    let alignment = mem::align_of::<T>();
-
-    debug_assert!(size_of != 0);
-    debug_assert!(old_capacity != 0);
-    debug_assert!(new_capacity != 0);
-
-    let ptr = heap::reallocate(old as *mut u8, allocation_size::<T>(old_capacity),
-                               allocation_size::<T>(new_capacity), alignment);
-    if ptr.is_null() { ::alloc::oom() }
-
-    ptr as *mut T
+    match try_reallocate::<T>(old, old_capacity, new_capacity) {
+        Ok(ptr) => ptr,
+        Err(..) => ::alloc::oom(),
+    }
 }
 
-/// Deallocates `old`
+/// Deallocates `old` through the `Global` allocator.
 ///
 /// ## Invariants
 ///   - `capacity` is non-zero.
 ///   - `T` is not a zero-sized-type.
 ///   - `old` is the appropriate size for `capacity` `T`s size and was allocated by
-///     the heap allocator.
+///     the `Global` allocator.
 #[inline]
 pub unsafe fn deallocate<T>(old: *mut T, capacity: uint) {
-    let size_of = mem::size_of::<T>();
-    let alignment = mem::align_of::<T>();
-
-    debug_assert!(size_of != 0);
+    debug_assert!(mem::size_of::<T>() != 0);
     debug_assert!(capacity != 0);
 
-    let size = allocation_size::<T>(size_of);
-    heap::deallocate(old as *mut u8, size, alignment)
+    Global.dealloc(old as *mut u8, Layout::array::<T>(capacity))
 }
 
 /// Gets the appropriate size for an allocation of `capacity` `T`s, checking for overflow.
@@ -91,3 +319,27 @@ fn allocation_size<T>(capacity: uint) -> uint {
     capacity.checked_mul(mem::size_of::<T>()).expect("capacity overflow")
 }
 
+#[cfg(test)]
+mod test {
+    use std::uint;
+    use super::{Layout, CollectionAllocErr};
+
+    #[test]
+    fn try_array_reports_overflow() {
+        match Layout::try_array::<u64>(uint::MAX) {
+            Err(CollectionAllocErr::CapacityOverflow) => {}
+            _ => panic!("expected CapacityOverflow"),
+        }
+    }
+
+    #[test]
+    fn try_array_computes_size_and_align() {
+        match Layout::try_array::<u32>(4) {
+            Ok(layout) => {
+                assert_eq!(layout.size(), 16);
+                assert_eq!(layout.align(), 4);
+            }
+            Err(..) => panic!("expected a valid layout"),
+        }
+    }
+}
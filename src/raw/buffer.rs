@@ -1,10 +1,16 @@
 use std::sync::atomic::{AtomicUint, AtomicPtr, Ordering};
 use std::num::Int;
+use std::cmp;
 use std::mem;
-use alloc::heap;
+
+use raw::alloc::{mod, Alloc, CollectionAllocErr, Global, Layout};
 
 /// A heap-allocated buffer with an atomic length and stored in an atomic pointer.
 ///
+/// The backing memory is obtained from a pluggable allocator `A`, defaulting to
+/// the system heap via `Global`. Downstream lock-free structures can therefore
+/// be built on arenas, pools, or instrumented allocators.
+///
 /// ## Unsafety
 ///
 /// Use of this structure directly is highly unsafe, since it supports unsynchronized
@@ -16,32 +22,47 @@ use alloc::heap;
 /// Capacity is also used as a drop flag, since if the capacity is 0 no cleanup is
 /// necessary.
 #[unsafe_no_drop_flag]
-pub struct Buffer<T> {
+pub struct Buffer<T, A = Global> {
     capacity: AtomicUint,
-    buffer: AtomicPtr<T>
+    buffer: AtomicPtr<T>,
+    alloc: A,
 }
 
-impl<T> Buffer<T> {
-    /// Allocate a new buffer with space for `capacity` `T`s.
+impl<T> Buffer<T, Global> {
+    /// Allocate a new buffer with space for `capacity` `T`s on the system heap.
     ///
     /// ## Panics
     ///
     /// Triggers `alloc::oom` if no memory can be allocated.
     #[inline]
-    pub fn allocate(capacity: uint) -> Buffer<T> {
-        Buffer {
-            capacity: AtomicUint::new(0),
-            buffer: AtomicPtr::new(unsafe { allocate_or_empty(capacity) })
-        }
+    pub fn allocate(capacity: uint) -> Buffer<T, Global> {
+        Buffer::allocate_in(capacity, Global)
     }
 
     /// Create a new empty buffer.
     ///
     /// Has the same behavior as `Buffer::allocate(0)`.
     #[inline]
-    pub fn empty() -> Buffer<T> {
+    pub fn empty() -> Buffer<T, Global> {
         Buffer::allocate(0)
     }
+}
+
+impl<T, A: Alloc> Buffer<T, A> {
+    /// Allocate a new buffer with space for `capacity` `T`s from `alloc`.
+    ///
+    /// ## Panics
+    ///
+    /// Triggers `alloc::oom` if no memory can be allocated.
+    #[inline]
+    pub fn allocate_in(capacity: uint, mut alloc: A) -> Buffer<T, A> {
+        let buffer = unsafe { allocate_or_empty(&mut alloc, capacity) };
+        Buffer {
+            capacity: AtomicUint::new(capacity),
+            buffer: AtomicPtr::new(buffer),
+            alloc: alloc,
+        }
+    }
 
     /// Get the capacity of this buffer.
     pub unsafe fn capacity(&self) -> &AtomicUint { &self.capacity }
@@ -82,6 +103,69 @@ impl<T> Buffer<T> {
         *self.get_mut(index, ordering) = data;
     }
 
+    /// Ensure the buffer can hold at least `used + extra` `T`s, growing it
+    /// with an amortized-doubling policy if it cannot.
+    ///
+    /// If `used + extra` already fits in the current capacity this is a no-op.
+    /// Otherwise the buffer is reallocated to `max(capacity * 2, used + extra)`,
+    /// so repeated single-element growth stays amortized O(1) rather than
+    /// O(n²). Zero-sized types have effectively infinite capacity and are
+    /// short-circuited.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `used + extra` overflows `uint`.
+    ///
+    /// ## Ordering
+    ///
+    /// The specified memory ordering is forwarded to `reallocate`, and used to
+    /// load the current capacity.
+    #[inline]
+    pub unsafe fn reserve(&mut self, used: uint, extra: uint, ordering: Ordering) {
+        if mem::size_of::<T>() == 0 { return }
+
+        let capacity = self.capacity.load(ordering);
+        let needed = used.checked_add(extra).expect("capacity overflow");
+
+        if needed <= capacity { return }
+
+        let new_cap = match capacity.checked_mul(2) {
+            Some(double) => cmp::max(double, needed),
+            None => needed,
+        };
+
+        self.reallocate(new_cap, ordering);
+    }
+
+    /// Double the capacity of this buffer, starting from a base of `4` when
+    /// it is currently empty.
+    ///
+    /// A convenience for resizing callers (such as ring buffers) that grow by
+    /// a constant factor and do not track a separate length. Zero-sized types
+    /// are short-circuited.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if doubling the capacity overflows `uint`.
+    ///
+    /// ## Ordering
+    ///
+    /// The specified memory ordering is forwarded to `reallocate`, and used to
+    /// load the current capacity.
+    #[inline]
+    pub unsafe fn grow(&mut self, ordering: Ordering) {
+        if mem::size_of::<T>() == 0 { return }
+
+        let capacity = self.capacity.load(ordering);
+        let new_cap = if capacity == 0 {
+            4
+        } else {
+            capacity.checked_mul(2).expect("capacity overflow")
+        };
+
+        self.reallocate(new_cap, ordering);
+    }
+
     /// Reallocate this buffer to a new size.
     ///
     /// ## Invariants
@@ -97,20 +181,65 @@ impl<T> Buffer<T> {
     ///   - Store the new capacity.
     #[inline]
     pub unsafe fn reallocate(&mut self, capacity: uint, ordering: Ordering) {
+        match self.try_reallocate(capacity, ordering) {
+            Ok(()) => {}
+            Err(..) => ::alloc::oom(),
+        }
+    }
+
+    /// Reallocate this buffer to a new size, returning an error rather than
+    /// aborting if the allocation fails or the size computation overflows.
+    ///
+    /// On `Err` the buffer is left untouched: the old allocation and its live
+    /// elements are preserved at their original capacity, so callers who wish
+    /// to degrade gracefully can retry with a smaller capacity.
+    ///
+    /// ## Invariants
+    ///
+    /// The new capacity must not be `0`.
+    ///
+    /// ## Ordering
+    ///
+    /// The specified memory ordering will be used to:
+    ///   - Swap the capacity to a sentinel.
+    ///   - Load the old buffer.
+    ///   - Store the new buffer.
+    ///   - Store the new capacity.
+    #[inline]
+    pub unsafe fn try_reallocate(&mut self, capacity: uint, ordering: Ordering)
+            -> Result<(), CollectionAllocErr> {
         debug_assert!(capacity != 0);
 
-        if mem::size_of::<T>() == 0 { return }
+        if mem::size_of::<T>() == 0 { return Ok(()) }
+
+        // Compute the new layout before disturbing the buffer so an overflow
+        // leaves the old allocation in place.
+        let new_layout = try!(Layout::try_array::<T>(capacity));
 
         let old_capacity = self.capacity.swap(0, ordering);
 
         let ptr = if old_capacity == 0 {
-            allocate(capacity)
+            match self.alloc.alloc(new_layout) {
+                Ok(ptr) => ptr,
+                Err(..) => return Err(CollectionAllocErr::AllocErr),
+            }
         } else {
-            reallocate(self.buffer.load(ordering), old_capacity, capacity)
+            let old = self.buffer.load(ordering) as *mut u8;
+            let old_layout = Layout::array::<T>(old_capacity);
+            match self.alloc.realloc(old, old_layout, new_layout) {
+                Ok(ptr) => ptr,
+                Err(..) => {
+                    // `realloc` failed: the old block is still valid. Restore the
+                    // capacity so the intact buffer is retained and freed on drop.
+                    self.capacity.store(old_capacity, ordering);
+                    return Err(CollectionAllocErr::AllocErr);
+                }
+            }
         };
 
-        self.buffer.store(ptr, ordering);
+        self.buffer.store(ptr as *mut T, ordering);
         self.capacity.store(capacity, ordering);
+        Ok(())
     }
 
     /// Deallocate this buffer using the specified memory ordering.
@@ -128,13 +257,15 @@ impl<T> Buffer<T> {
         if mem::size_of::<T>() == 0 { return }
 
         let capacity = self.capacity.swap(0, ordering);
-        let buffer = self.buffer.swap(empty(), ordering);
-        deallocate(buffer, capacity);
+        let buffer = self.buffer.swap(alloc::empty(), ordering);
+        if capacity != 0 {
+            self.alloc.dealloc(buffer as *mut u8, Layout::array::<T>(capacity));
+        }
     }
 }
 
 #[unsafe_destructor]
-impl<T> Drop for Buffer<T> {
+impl<T, A: Alloc> Drop for Buffer<T, A> {
     /// Deallocates using Ordering::SeqCst.
     ///
     /// No-op if `mem::size_of::<T>() == 0` or the capacity is `0`.
@@ -148,80 +279,61 @@ impl<T> Drop for Buffer<T> {
 }
 
 #[inline]
-unsafe fn empty<T>() -> *mut T { 1u as *mut T }
-
-#[inline]
-unsafe fn allocate_or_empty<T>(capacity: uint) -> *mut T {
+unsafe fn allocate_or_empty<T, A: Alloc>(alloc: &mut A, capacity: uint) -> *mut T {
     if mem::size_of::<T>() == 0 || capacity == 0 {
-        empty()
+        alloc::empty()
     } else {
-        allocate(capacity)
+        match alloc.alloc(Layout::array::<T>(capacity)) {
+            Ok(ptr) => ptr as *mut T,
+            Err(..) => ::alloc::oom(),
+        }
     }
 }
 
-/// UB if:
-///   - capacity == 0
-///   - size_of::<T> == 0
-#[inline]
-unsafe fn allocate<T>(capacity: uint) -> *mut T {
-    let size_of = mem::size_of::<T>();
-    let alignment = mem::align_of::<T>();
-
-    debug_assert!(size_of != 0);
-    debug_assert!(capacity != 0);
+#[cfg(test)]
+mod test {
+    use std::uint;
+    use std::sync::atomic::Ordering;
+    use super::Buffer;
 
-    let size = allocation_size::<T>(size_of);
-    let ptr = heap::allocate(size, alignment);
-    if ptr.is_null() { ::alloc::oom() }
+    #[test]
+    fn reserve_doubles_capacity() {
+        unsafe {
+            let mut buffer: Buffer<int> = Buffer::empty();
 
-    ptr as *mut T
-}
+            // Empty buffer grows to exactly the amount needed.
+            buffer.reserve(0, 3, Ordering::SeqCst);
+            assert_eq!(buffer.capacity().load(Ordering::SeqCst), 3);
 
-/// UB if:
-///   - new_capacity == 0
-///   - size_of::<T> == 0
-///   - old is not allocated by the heap allocator
-///   - old_capacity is not the capacity of old
-#[inline]
-unsafe fn reallocate<T>(old: *mut T, old_capacity: uint, new_capacity: uint) -> *mut T {
-    let size_of = mem::size_of::<T>();
-    let alignment = mem::align_of::<T>();
-
-    debug_assert!(size_of != 0);
-    debug_assert!(old_capacity != 0);
-    debug_assert!(new_capacity != 0);
-
-    let ptr = heap::reallocate(old as *mut u8, allocation_size::<T>(old_capacity),
-                               allocation_size::<T>(new_capacity), alignment);
-    if ptr.is_null() { ::alloc::oom() }
-
-    ptr as *mut T
-}
+            // A one-past-capacity request doubles: max(3 * 2, 4) == 6.
+            buffer.reserve(3, 1, Ordering::SeqCst);
+            assert_eq!(buffer.capacity().load(Ordering::SeqCst), 6);
 
-/// UB if:
-///   - capacity == 0
-///   - size_of::<T> == 0
-///   - old is not allocated by the heap allocator
-///   - capacity is not the capacity of old
-#[inline]
-unsafe fn deallocate<T>(old: *mut T, capacity: uint) {
-    let size_of = mem::size_of::<T>();
-    let alignment = mem::align_of::<T>();
+            // Already-sufficient capacity is left untouched.
+            buffer.reserve(2, 2, Ordering::SeqCst);
+            assert_eq!(buffer.capacity().load(Ordering::SeqCst), 6);
+        }
+    }
 
-    debug_assert!(size_of != 0);
-    debug_assert!(capacity != 0);
+    #[test]
+    #[should_fail]
+    fn reserve_overflow_fails() {
+        unsafe {
+            let mut buffer: Buffer<int> = Buffer::empty();
+            buffer.reserve(uint::MAX, 1, Ordering::SeqCst);
+        }
+    }
 
-    let size = allocation_size::<T>(size_of);
-    heap::deallocate(old as *mut u8, size, alignment)
-}
+    #[test]
+    fn grow_starts_at_four_then_doubles() {
+        unsafe {
+            let mut buffer: Buffer<int> = Buffer::empty();
 
-/// Capacity should not == 0 or this will give not-usable results
-/// same for size_of::<T>
-#[inline]
-fn allocation_size<T>(capacity: uint) -> uint {
-    debug_assert!(capacity != 0);
-    debug_assert!(mem::size_of::<T>() != 0);
+            buffer.grow(Ordering::SeqCst);
+            assert_eq!(buffer.capacity().load(Ordering::SeqCst), 4);
 
-    capacity.checked_mul(mem::size_of::<T>()).expect("capacity overflow")
+            buffer.grow(Ordering::SeqCst);
+            assert_eq!(buffer.capacity().load(Ordering::SeqCst), 8);
+        }
+    }
 }
-
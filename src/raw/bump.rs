@@ -0,0 +1,208 @@
+use std::cmp;
+use std::num::Int;
+use std::ptr;
+use std::sync::atomic::{AtomicUint, Ordering};
+
+use raw::alloc::{mod, Alloc, AllocErr, Layout};
+use raw::buffer::Buffer;
+
+/// A lock-free bump allocator backed by a single `raw::buffer::Buffer<u8>`.
+///
+/// Allocation is a `compare_and_swap` loop over an atomic offset: the current
+/// offset is aligned up to the request's alignment, the request's size is
+/// reserved, and the interior pointer is handed back. When the region is
+/// exhausted allocation fails with `AllocErr` rather than aborting.
+///
+/// This gives a fast, contention-light arena for short-lived lock-free node
+/// allocations, and implements `Alloc` (for `&Bump`) so `Buffer`-based
+/// structures can be backed by a bump region.
+///
+/// ## Unsafety
+///
+/// Memory handed out by `alloc` is only valid for the lifetime of the `Bump`,
+/// and `reset` reclaims the whole region at once, so it is the caller's
+/// responsibility to ensure no outstanding references remain.
+pub struct Bump {
+    buffer: Buffer<u8>,
+    offset: AtomicUint,
+    capacity: uint,
+}
+
+impl Bump {
+    /// Create a bump allocator over a fresh region of `capacity` bytes.
+    pub fn with_capacity(capacity: uint) -> Bump {
+        let mut buffer = Buffer::empty();
+        if capacity != 0 {
+            unsafe { buffer.reallocate(capacity, Ordering::SeqCst); }
+        }
+
+        Bump {
+            buffer: buffer,
+            offset: AtomicUint::new(0),
+            capacity: capacity,
+        }
+    }
+
+    /// Allocate a block fitting `layout` from the region.
+    ///
+    /// Returns `Err(AllocErr)` if the region is exhausted or the aligned
+    /// request would overflow `uint`. Zero-sized requests never consume the
+    /// region and yield the shared empty sentinel.
+    ///
+    /// For a non-zero-sized request, the returned pointer is aligned to
+    /// `layout.align()` regardless of the
+    /// alignment of the backing `Buffer<u8>`, which is only guaranteed to be
+    /// byte-aligned: the *real* address is rounded up, not just the offset, so
+    /// some bytes at the front of the region may be skipped.
+    pub fn alloc(&self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let size = layout.size();
+        let align = layout.align();
+
+        assert!(align != 0 && (align & (align - 1)) == 0,
+                "alignment must be a non-zero power of two");
+
+        if size == 0 { return Ok(unsafe { alloc::empty() }) }
+
+        let base = unsafe { self.buffer.get(0, Ordering::Acquire) } as uint;
+
+        loop {
+            let current = self.offset.load(Ordering::Acquire);
+
+            // Align the real address (`base + current`), then translate back to
+            // an offset so `base` alignment weaker than `align` is accounted for.
+            let aligned_addr = match base.checked_add(current).and_then(|addr| align_up(addr, align)) {
+                Some(addr) => addr,
+                None => return Err(AllocErr),
+            };
+            let aligned = aligned_addr - base;
+            let end = match aligned.checked_add(size) {
+                Some(end) => end,
+                None => return Err(AllocErr),
+            };
+
+            if end > self.capacity { return Err(AllocErr) }
+
+            if self.offset.compare_and_swap(current, end, Ordering::AcqRel) == current {
+                return Ok(unsafe { self.buffer.get_mut(aligned, Ordering::Acquire) });
+            }
+        }
+    }
+
+    /// The number of bytes consumed from the region so far, including any
+    /// alignment padding skipped between blocks.
+    #[inline]
+    pub fn used(&self) -> uint { self.offset.load(Ordering::Acquire) }
+
+    /// The current fill level, in bytes. Alias of `used`.
+    #[inline]
+    pub fn level(&self) -> uint { self.used() }
+
+    /// The total size of the region, in bytes.
+    #[inline]
+    pub fn capacity(&self) -> uint { self.capacity }
+
+    /// Reclaim the whole region, resetting the offset to `0`.
+    ///
+    /// ## Unsafety
+    ///
+    /// Only safe when no references into previously-allocated blocks remain;
+    /// those blocks are handed back out by subsequent calls to `alloc`.
+    #[inline]
+    pub unsafe fn reset(&self) {
+        self.offset.store(0, Ordering::Release);
+    }
+}
+
+impl<'a> Alloc for &'a Bump {
+    #[inline]
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        Bump::alloc(*self, layout)
+    }
+
+    /// Bump regions cannot free in place, so `realloc` allocates a fresh block
+    /// and copies the overlapping prefix across.
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: *mut u8, old: Layout, new: Layout)
+            -> Result<*mut u8, AllocErr> {
+        match Bump::alloc(*self, new) {
+            Ok(dst) => {
+                ptr::copy_memory(dst, ptr as *const u8, cmp::min(old.size(), new.size()));
+                Ok(dst)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// No-op: individual blocks are reclaimed only by `reset`.
+    #[inline]
+    unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+/// Round `offset` up to a multiple of `align`, returning `None` on overflow.
+///
+/// ## Invariants
+///   - `align` is a non-zero power of two (enforced by `Bump::alloc`).
+#[inline]
+fn align_up(offset: uint, align: uint) -> Option<uint> {
+    debug_assert!(align != 0 && (align & (align - 1)) == 0);
+    offset.checked_add(align - 1).map(|rounded| rounded & !(align - 1))
+}
+
+#[cfg(test)]
+mod test {
+    use std::uint;
+    use raw::alloc::Layout;
+    use super::{Bump, align_up};
+
+    #[test]
+    fn align_up_rounds_to_power_of_two() {
+        assert_eq!(align_up(0, 8), Some(0));
+        assert_eq!(align_up(1, 8), Some(8));
+        assert_eq!(align_up(8, 8), Some(8));
+        assert_eq!(align_up(9, 4), Some(12));
+        assert_eq!(align_up(uint::MAX, 8), None);
+    }
+
+    #[test]
+    fn alloc_returns_aligned_pointers() {
+        let bump = Bump::with_capacity(64);
+
+        // A byte, then an 8-aligned block: the second must be aligned regardless
+        // of where the first landed.
+        match bump.alloc(Layout::from_size_align(1, 1)) {
+            Ok(..) => {}
+            Err(..) => panic!("expected the first allocation to succeed"),
+        }
+        match bump.alloc(Layout::from_size_align(4, 8)) {
+            Ok(ptr) => assert_eq!(ptr as uint % 8, 0),
+            Err(..) => panic!("expected the aligned allocation to succeed"),
+        }
+    }
+
+    #[test]
+    fn alloc_fails_when_exhausted() {
+        let bump = Bump::with_capacity(16);
+        match bump.alloc(Layout::from_size_align(32, 1)) {
+            Ok(..) => panic!("expected exhaustion"),
+            Err(..) => {}
+        }
+    }
+
+    #[test]
+    fn reset_reclaims_the_region() {
+        let bump = Bump::with_capacity(8);
+        match bump.alloc(Layout::from_size_align(8, 1)) {
+            Ok(..) => {}
+            Err(..) => panic!("expected the allocation to succeed"),
+        }
+        assert_eq!(bump.used(), 8);
+
+        unsafe { bump.reset(); }
+        assert_eq!(bump.used(), 0);
+
+        match bump.alloc(Layout::from_size_align(8, 1)) {
+            Ok(..) => {}
+            Err(..) => panic!("expected the region to be reusable after reset"),
+        }
+    }
+}
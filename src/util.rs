@@ -0,0 +1,192 @@
+use std::mem;
+use std::ptr;
+use std::raw::Slice as RawSlice;
+use std::uint;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering;
+
+use raw::buffer::Buffer;
+
+/// A safe, owning collection backed by a `raw::buffer::Buffer<T>`.
+///
+/// `OwnedBuffer` tracks its logical length and capacity in plain fields and
+/// never exposes the buffer's memory orderings, so callers get a usable
+/// growable array without reasoning about the unsynchronized atomic internals
+/// or juggling raw pointers. Live elements are dropped when the buffer is.
+///
+/// Because a single owner has exclusive access, every access to the underlying
+/// buffer uses `Ordering::Relaxed`.
+pub struct OwnedBuffer<T> {
+    buffer: Buffer<T>,
+    length: uint,
+    capacity: uint,
+}
+
+impl<T> OwnedBuffer<T> {
+    /// Create an empty buffer with room for at least `capacity` elements.
+    pub fn with_capacity(capacity: uint) -> OwnedBuffer<T> {
+        let mut buffer = Buffer::empty();
+
+        let capacity = if mem::size_of::<T>() == 0 {
+            uint::MAX
+        } else if capacity == 0 {
+            0
+        } else {
+            unsafe { buffer.reallocate(capacity, Ordering::Relaxed); }
+            capacity
+        };
+
+        OwnedBuffer { buffer: buffer, length: 0, capacity: capacity }
+    }
+
+    /// Create an empty buffer that has not yet allocated.
+    #[inline]
+    pub fn new() -> OwnedBuffer<T> {
+        OwnedBuffer::with_capacity(0)
+    }
+
+    /// The number of live elements in the buffer.
+    #[inline]
+    pub fn len(&self) -> uint { self.length }
+
+    /// Whether the buffer holds no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.length == 0 }
+
+    /// The number of elements the buffer can hold without reallocating.
+    #[inline]
+    pub fn capacity(&self) -> uint { self.capacity }
+
+    /// Append an element, growing the buffer if it is full.
+    pub fn push(&mut self, value: T) {
+        if self.length == self.capacity {
+            self.grow();
+        }
+
+        unsafe { ptr::write(self.buffer.get_mut(self.length, Ordering::Relaxed), value); }
+        self.length += 1;
+    }
+
+    /// Remove and return the last element, or `None` if the buffer is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.length == 0 { return None }
+
+        self.length -= 1;
+        Some(unsafe { ptr::read(self.buffer.get(self.length, Ordering::Relaxed)) })
+    }
+
+    /// View the live elements as a slice.
+    ///
+    /// The returned slice borrows `self`, so its lifetime is tied to the
+    /// buffer rather than to a throwaway copy of the data pointer.
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        let data = unsafe { self.buffer.get(0, Ordering::Relaxed) };
+        unsafe { mem::transmute(RawSlice { data: data, len: self.length }) }
+    }
+
+    /// View the live elements as a mutable slice.
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        let data = unsafe { self.buffer.get(0, Ordering::Relaxed) };
+        unsafe { mem::transmute(RawSlice { data: data, len: self.length }) }
+    }
+
+    /// Double the buffer, forwarding to the amortized-growth policy on `Buffer`.
+    fn grow(&mut self) {
+        if mem::size_of::<T>() == 0 {
+            self.capacity = uint::MAX;
+            return;
+        }
+
+        unsafe {
+            self.buffer.grow(Ordering::Relaxed);
+            self.capacity = self.buffer.capacity().load(Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T> Deref<[T]> for OwnedBuffer<T> {
+    #[inline]
+    fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+impl<T> DerefMut<[T]> for OwnedBuffer<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] { self.as_mut_slice() }
+}
+
+#[unsafe_destructor]
+impl<T> Drop for OwnedBuffer<T> {
+    /// Drops every live element; the owned `Buffer` releases the allocation.
+    fn drop(&mut self) {
+        unsafe {
+            for index in range(0, self.length) {
+                ptr::read(self.buffer.get(index, Ordering::Relaxed));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUint, ATOMIC_UINT_INIT, Ordering};
+    use super::OwnedBuffer;
+
+    #[test]
+    fn push_pop_round_trips() {
+        let mut buffer: OwnedBuffer<int> = OwnedBuffer::new();
+        for i in range(0i, 10) { buffer.push(i); }
+
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer[0], 0);
+        assert_eq!(buffer[9], 9);
+
+        assert_eq!(buffer.pop(), Some(9));
+        assert_eq!(buffer.len(), 9);
+    }
+
+    #[test]
+    fn pop_empty_is_none() {
+        let mut buffer: OwnedBuffer<int> = OwnedBuffer::new();
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn grows_past_initial_capacity() {
+        let mut buffer: OwnedBuffer<int> = OwnedBuffer::with_capacity(2);
+        assert_eq!(buffer.capacity(), 2);
+
+        buffer.push(1);
+        buffer.push(2);
+        buffer.push(3);
+
+        assert!(buffer.capacity() >= 3);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.as_slice(), [1, 2, 3].as_slice());
+    }
+
+    static DROPS: AtomicUint = ATOMIC_UINT_INIT;
+
+    struct Noisy;
+
+    impl Drop for Noisy {
+        fn drop(&mut self) { DROPS.fetch_add(1, Ordering::SeqCst); }
+    }
+
+    #[test]
+    fn drops_live_elements() {
+        DROPS.store(0, Ordering::SeqCst);
+
+        {
+            let mut buffer: OwnedBuffer<Noisy> = OwnedBuffer::new();
+            buffer.push(Noisy);
+            buffer.push(Noisy);
+            buffer.push(Noisy);
+            let _ = buffer.pop(); // drops one immediately
+        }
+
+        // One from `pop`, two live elements dropped with the buffer.
+        assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+    }
+}